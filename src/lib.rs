@@ -0,0 +1,573 @@
+pub mod par;
+
+use bytemuck::cast;
+use clap::ValueEnum;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::{
+    fmt::Write as _,
+    fs,
+    io::{self, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+const CHARA_KEY: &'static [u8; 512] = include_bytes!("keys/chara_key.bin");
+const CHARA2_KEY: &'static [u8; 512] = include_bytes!("keys/chara2_key.bin");
+
+const CHARA_MAGIC: [u8; 4] = *b"\xAC\xC5\x8B\x99";
+const CHARA2_MAGIC: [u8; 4] = *b"\x01\x6E\x58\xE4";
+
+/// Size in bytes of an on-disk key file: a 4-byte magic followed by the
+/// 512-byte key.
+const KEY_FILE_SIZE: usize = 4 + 512;
+
+pub const READ_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+pub const WRITE_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+/// Size of a parallel segment, in blocks. Each block is 8 bytes, so this is
+/// a 8 MiB segment, matching `READ_BUFFER_SIZE`.
+pub const SEGMENT_BLOCKS: usize = READ_BUFFER_SIZE / 8;
+
+/// Below this size, the single-threaded path is used even if more threads
+/// are available, since the overhead of spinning up a thread pool outweighs
+/// the gain.
+pub const PARALLEL_THRESHOLD: u64 = SEGMENT_BLOCKS as u64 * 8;
+
+#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
+pub enum Mode {
+    /// Automatically select mode based on input file name.
+    Auto,
+
+    /// Decrypt file.
+    Decrypt,
+
+    /// Encrypt file.
+    Encrypt,
+}
+
+/// A named 64-word key paired with the 4-byte magic of the PAR files it
+/// decrypts, the unit of data in a `KeyRegistry`.
+#[derive(Clone)]
+pub struct KeyEntry {
+    pub name: String,
+    pub magic: [u8; 4],
+    pub key: [u64; 64],
+}
+
+/// A registry mapping PAR magic bytes to named keys, replacing a fixed set
+/// of PAR types with data that can be extended at runtime. Starts out with
+/// `chara` and `chara2` registered as built-in defaults; `load_key_file`
+/// and `load_keys_dir` add more from user-supplied key files.
+#[derive(Clone, Default)]
+pub struct KeyRegistry {
+    pub entries: Vec<KeyEntry>,
+}
+
+impl KeyRegistry {
+    /// A registry containing only the built-in `chara`/`chara2` keys.
+    pub fn with_builtins() -> Self {
+        Self {
+            entries: vec![
+                KeyEntry {
+                    name: "chara".to_string(),
+                    magic: CHARA_MAGIC,
+                    key: cast(*CHARA_KEY),
+                },
+                KeyEntry {
+                    name: "chara2".to_string(),
+                    magic: CHARA2_MAGIC,
+                    key: cast(*CHARA2_KEY),
+                },
+            ],
+        }
+    }
+
+    /// Loads a single key file — a 4-byte magic followed by a 512-byte key
+    /// — and registers it under the file's stem.
+    pub fn load_key_file(&mut self, path: &Path) -> io::Result<()> {
+        let bytes = fs::read(path)?;
+
+        if bytes.len() != KEY_FILE_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "{path:?} is not a valid key file (expected {KEY_FILE_SIZE} bytes, got {})",
+                    bytes.len()
+                ),
+            ));
+        }
+
+        let magic: [u8; 4] = bytes[..4].try_into().unwrap();
+        let key_bytes: [u8; 512] = bytes[4..].try_into().unwrap();
+
+        let name = path
+            .file_stem()
+            .and_then(|stem| stem.to_str())
+            .unwrap_or("key")
+            .to_string();
+
+        self.entries.push(KeyEntry {
+            name,
+            magic,
+            key: cast(key_bytes),
+        });
+
+        Ok(())
+    }
+
+    /// Loads every key file found directly inside `dir`.
+    pub fn load_keys_dir(&mut self, dir: &Path) -> io::Result<()> {
+        let mut dir_entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+        dir_entries.sort_by_key(|entry| entry.file_name());
+
+        for entry in dir_entries {
+            if entry.file_type()?.is_file() {
+                self.load_key_file(&entry.path())?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Looks up a registered key by name.
+    pub fn by_name(&self, name: &str) -> Option<&KeyEntry> {
+        self.entries.iter().find(|entry| entry.name == name)
+    }
+
+    /// Returns every entry whose magic matches, so callers can tell an
+    /// unambiguous match from a collision between two registered keys.
+    pub fn detect(&self, magic: &[u8; 4]) -> Vec<&KeyEntry> {
+        self.entries
+            .iter()
+            .filter(|entry| &entry.magic == magic)
+            .collect()
+    }
+}
+
+#[inline]
+fn encrypt_block(i: usize, block: u64, key: &[u64; 64]) -> u64 {
+    block.rotate_right((i % 64) as u32) ^ key[i % 64]
+}
+
+#[inline]
+fn decrypt_block(i: usize, block: u64, key: &[u64; 64]) -> u64 {
+    (block ^ key[i % 64]).rotate_left((i % 64) as u32)
+}
+
+/// Encrypts `reader` into `writer`, single-threaded, via `CipherWriter`.
+pub fn encrypt<R: Read, W: Write>(mut reader: R, writer: W, key: &[u64; 64]) {
+    let mut cipher = CipherWriter::new(writer, *key, Mode::Encrypt);
+    io::copy(&mut reader, &mut cipher).unwrap();
+    cipher.finish().unwrap();
+}
+
+/// Decrypts `reader` into `writer`, single-threaded, via `CipherWriter`.
+pub fn decrypt<R: Read, W: Write>(mut reader: R, writer: W, key: &[u64; 64]) {
+    let mut cipher = CipherWriter::new(writer, *key, Mode::Decrypt);
+    io::copy(&mut reader, &mut cipher).unwrap();
+    cipher.finish().unwrap();
+}
+
+/// Reads `reader` in `SEGMENT_BLOCKS`-sized segments, transforms each
+/// segment with a position-indexed Rayon pipeline (so every block only
+/// depends on its own global index), and writes the transformed segments
+/// back in order. The final segment is zero-padded to a whole number of
+/// blocks, the same as `CipherReader`/`CipherWriter` pad their final block.
+///
+/// `transform` receives the global block index and the raw little-endian
+/// block and returns the transformed block.
+pub fn transform_parallel<R, W, F>(mut reader: R, mut writer: W, key: &[u64; 64], transform: F)
+where
+    R: Read,
+    W: Write,
+    F: Fn(usize, u64, &[u64; 64]) -> u64 + Sync,
+{
+    let segment_bytes = SEGMENT_BLOCKS * 8;
+    let mut buf = vec![0u8; segment_bytes];
+    let mut base = 0usize;
+
+    loop {
+        let mut filled = 0;
+
+        while filled < segment_bytes {
+            match reader.read(&mut buf[filled..]).unwrap() {
+                0 => break,
+                n => filled += n,
+            }
+        }
+
+        if filled == 0 {
+            break;
+        }
+
+        let padded_len = filled.next_multiple_of(8);
+        buf[filled..padded_len].fill(0);
+
+        let out: Vec<[u8; 8]> = buf[..padded_len]
+            .par_chunks_exact(8)
+            .enumerate()
+            .map(|(local, chunk)| {
+                let i = base + local;
+                let block = u64::from_le_bytes(chunk.try_into().unwrap());
+                transform(i, block, key).to_le_bytes()
+            })
+            .collect();
+
+        for block in &out {
+            writer.write_all(block).unwrap();
+        }
+
+        base += padded_len / 8;
+
+        if filled < segment_bytes {
+            break;
+        }
+    }
+}
+
+pub fn encrypt_parallel<R: Read, W: Write>(reader: R, writer: W, key: &[u64; 64]) {
+    transform_parallel(reader, writer, key, encrypt_block);
+}
+
+pub fn decrypt_parallel<R: Read, W: Write>(reader: R, writer: W, key: &[u64; 64]) {
+    transform_parallel(reader, writer, key, decrypt_block);
+}
+
+/// A streaming adapter that applies the block cipher to bytes read from an
+/// inner reader. Blocks are buffered internally and transformed as they are
+/// consumed; the final, short block is zero-padded once the inner reader
+/// reaches EOF.
+pub struct CipherReader<R: Read> {
+    inner: R,
+    key: [u64; 64],
+    mode: Mode,
+    block_index: usize,
+    pending: Vec<u8>,
+    eof: bool,
+}
+
+impl<R: Read> CipherReader<R> {
+    /// Creates a new `CipherReader`. `mode` must be `Mode::Encrypt` or
+    /// `Mode::Decrypt`; `key` is typically a `KeyRegistry` entry's key,
+    /// resolved via `KeyRegistry::detect` or `KeyRegistry::by_name`.
+    pub fn new(inner: R, key: [u64; 64], mode: Mode) -> Self {
+        Self {
+            inner,
+            key,
+            mode,
+            block_index: 0,
+            pending: Vec::new(),
+            eof: false,
+        }
+    }
+
+    fn fill_pending(&mut self) -> io::Result<()> {
+        if self.eof || !self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let mut block = [0u8; 8];
+        let mut filled = 0;
+
+        while filled < 8 {
+            match self.inner.read(&mut block[filled..])? {
+                0 => break,
+                n => filled += n,
+            }
+        }
+
+        if filled == 0 {
+            self.eof = true;
+            return Ok(());
+        }
+
+        if filled < 8 {
+            block[filled..].fill(0);
+            self.eof = true;
+        }
+
+        let val = match self.mode {
+            Mode::Encrypt => encrypt_block(self.block_index, u64::from_le_bytes(block), &self.key),
+            Mode::Decrypt => decrypt_block(self.block_index, u64::from_le_bytes(block), &self.key),
+            Mode::Auto => unreachable!("CipherReader requires a concrete Mode"),
+        };
+
+        self.block_index += 1;
+        self.pending = val.to_le_bytes().to_vec();
+
+        Ok(())
+    }
+}
+
+impl<R: Read> Read for CipherReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+
+        self.fill_pending()?;
+
+        if self.pending.is_empty() {
+            return Ok(0);
+        }
+
+        let n = buf.len().min(self.pending.len());
+        buf[..n].copy_from_slice(&self.pending[..n]);
+        self.pending.drain(..n);
+
+        Ok(n)
+    }
+}
+
+/// A streaming adapter that applies the block cipher to bytes written to an
+/// inner writer. Bytes are buffered into 8-byte blocks and transformed as
+/// each block fills; any trailing partial block is zero-padded and flushed
+/// by `finish`, which is also called automatically on drop.
+pub struct CipherWriter<W: Write> {
+    inner: W,
+    key: [u64; 64],
+    mode: Mode,
+    block_index: usize,
+    buf: Vec<u8>,
+    finished: bool,
+}
+
+impl<W: Write> CipherWriter<W> {
+    /// Creates a new `CipherWriter`. `mode` must be `Mode::Encrypt` or
+    /// `Mode::Decrypt`; `key` is typically a `KeyRegistry` entry's key.
+    pub fn new(inner: W, key: [u64; 64], mode: Mode) -> Self {
+        Self {
+            inner,
+            key,
+            mode,
+            block_index: 0,
+            buf: Vec::with_capacity(8),
+            finished: false,
+        }
+    }
+
+    fn write_block(&mut self, block: [u8; 8]) -> io::Result<()> {
+        let val = match self.mode {
+            Mode::Encrypt => encrypt_block(self.block_index, u64::from_le_bytes(block), &self.key),
+            Mode::Decrypt => decrypt_block(self.block_index, u64::from_le_bytes(block), &self.key),
+            Mode::Auto => unreachable!("CipherWriter requires a concrete Mode"),
+        };
+
+        self.block_index += 1;
+        self.inner.write_all(&val.to_le_bytes())
+    }
+
+    /// Flushes a zero-padded final block if any bytes are still buffered.
+    /// Idempotent; called automatically on drop, but callers that need to
+    /// observe I/O errors from the final block should call this explicitly.
+    pub fn finish(&mut self) -> io::Result<()> {
+        if self.finished {
+            return Ok(());
+        }
+
+        self.finished = true;
+
+        if !self.buf.is_empty() {
+            let mut block = [0u8; 8];
+            block[..self.buf.len()].copy_from_slice(&self.buf);
+            self.buf.clear();
+            self.write_block(block)?;
+        }
+
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Write for CipherWriter<W> {
+    fn write(&mut self, mut data: &[u8]) -> io::Result<usize> {
+        let total = data.len();
+
+        while !data.is_empty() {
+            let need = 8 - self.buf.len();
+            let take = need.min(data.len());
+            self.buf.extend_from_slice(&data[..take]);
+            data = &data[take..];
+
+            if self.buf.len() == 8 {
+                let block: [u8; 8] = self.buf.as_slice().try_into().unwrap();
+                self.buf.clear();
+                self.write_block(block)?;
+            }
+        }
+
+        Ok(total)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: Write> Drop for CipherWriter<W> {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+/// A streaming adapter that digests bytes as they are read, used to verify
+/// plaintext integrity without buffering the whole stream in memory.
+pub struct HashWrapperReader<R: Read> {
+    inner: R,
+    hasher: Sha256,
+}
+
+impl<R: Read> HashWrapperReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+
+    /// Consumes the wrapper and returns the digest of everything read so far.
+    pub fn finalize(self) -> [u8; 32] {
+        self.hasher.finalize().into()
+    }
+}
+
+impl<R: Read> Read for HashWrapperReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.hasher.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+/// Path of the SHA-256 sidecar that travels alongside an encrypted PAR file.
+pub fn sidecar_path(par_path: &Path) -> PathBuf {
+    let mut sidecar = par_path.as_os_str().to_owned();
+    sidecar.push(".sha256");
+    PathBuf::from(sidecar)
+}
+
+/// Writes a plaintext digest to its sidecar file, as a hex string.
+pub fn write_sidecar(path: &Path, digest: [u8; 32]) -> io::Result<()> {
+    std::fs::write(path, to_hex(&digest))
+}
+
+/// Reads and parses a digest previously written by `write_sidecar`.
+pub fn read_sidecar(path: &Path) -> io::Result<[u8; 32]> {
+    let text = std::fs::read_to_string(path)?;
+    from_hex(text.trim())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "malformed sha256 sidecar"))
+}
+
+fn to_hex(bytes: &[u8; 32]) -> String {
+    let mut s = String::with_capacity(64);
+    for b in bytes {
+        write!(s, "{b:02x}").unwrap();
+    }
+    s
+}
+
+fn from_hex(s: &str) -> Option<[u8; 32]> {
+    if s.len() != 64 {
+        return None;
+    }
+
+    let mut out = [0u8; 32];
+
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&s[i * 2..i * 2 + 2], 16).ok()?;
+    }
+
+    Some(out)
+}
+
+/// Decrypts `reader` fully (discarding the plaintext) under each registered
+/// key in turn and returns the first entry whose plaintext digest matches
+/// `expected_digest`. Leaves `reader`'s position unspecified; callers
+/// should seek back to the start before reusing it.
+pub fn detect_by_digest<'registry, R: Read + Seek>(
+    mut reader: R,
+    registry: &'registry KeyRegistry,
+    expected_digest: [u8; 32],
+) -> Option<&'registry KeyEntry> {
+    for entry in &registry.entries {
+        reader.seek(SeekFrom::Start(0)).ok()?;
+
+        let cipher = CipherReader::new(&mut reader, entry.key, Mode::Decrypt);
+        let mut hasher = HashWrapperReader::new(cipher);
+        io::copy(&mut hasher, &mut io::sink()).ok()?;
+
+        if hasher.finalize() == expected_digest {
+            return Some(entry);
+        }
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_key() -> [u64; 64] {
+        let mut key = [0u64; 64];
+
+        for (i, k) in key.iter_mut().enumerate() {
+            *k = i as u64 ^ 0x9E3779B97F4A7C15;
+        }
+
+        key
+    }
+
+    fn padded(mut data: Vec<u8>) -> Vec<u8> {
+        let padded_len = data.len().next_multiple_of(8);
+        data.resize(padded_len, 0);
+        data
+    }
+
+    #[test]
+    fn encrypt_decrypt_round_trips() {
+        let key = test_key();
+        let plaintext = b"the quick brown fox jumps over the lazy dog".to_vec();
+
+        let mut ciphertext = Vec::new();
+        encrypt(&plaintext[..], &mut ciphertext, &key);
+
+        let mut roundtrip = Vec::new();
+        decrypt(&ciphertext[..], &mut roundtrip, &key);
+
+        assert_eq!(roundtrip, padded(plaintext));
+    }
+
+    #[test]
+    fn parallel_path_matches_serial_path() {
+        let key = test_key();
+
+        // Lengths that land on, just below, and just above block (8) and
+        // segment (`SEGMENT_BLOCKS * 8`) boundaries, so the parallel path's
+        // short final segment gets exercised alongside full ones.
+        let segment = SEGMENT_BLOCKS * 8;
+
+        for len in [0, 1, 7, 8, 9, 100, 4096, 4097, segment - 1, segment, segment + 1] {
+            let plaintext: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+
+            let mut serial = Vec::new();
+            encrypt(&plaintext[..], &mut serial, &key);
+
+            let mut parallel = Vec::new();
+            encrypt_parallel(&plaintext[..], &mut parallel, &key);
+
+            assert_eq!(serial, parallel, "encrypt mismatch at len {len}");
+
+            let mut serial_roundtrip = Vec::new();
+            decrypt(&serial[..], &mut serial_roundtrip, &key);
+
+            let mut parallel_roundtrip = Vec::new();
+            decrypt_parallel(&parallel[..], &mut parallel_roundtrip, &key);
+
+            assert_eq!(
+                serial_roundtrip, parallel_roundtrip,
+                "decrypt mismatch at len {len}"
+            );
+            assert_eq!(serial_roundtrip, padded(plaintext), "round trip mismatch at len {len}");
+        }
+    }
+}
@@ -1,23 +1,16 @@
-#![feature(iter_array_chunks)]
-
-mod array_chunks_pad;
-use array_chunks_pad::ArrayChunksPadExtension;
-
-use bytemuck::cast_slice;
-use clap::{CommandFactory, Parser, ValueEnum};
+use clap::{CommandFactory, Parser};
 use dialoguer::{Confirm, Select};
 use spinners::{Spinner, Spinners};
 use std::{
-    fs::File,
-    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    fs::{self, File},
+    io::{copy, sink, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
     path::PathBuf,
 };
-
-const CHARA_KEY: &'static [u8; 512] = include_bytes!("keys/chara_key.bin");
-const CHARA2_KEY: &'static [u8; 512] = include_bytes!("keys/chara2_key.bin");
-
-const READ_BUFFER_SIZE: usize = 8 * 1024 * 1024;
-const WRITE_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+use yagami_decryption_agency::{
+    decrypt, decrypt_parallel, detect_by_digest, encrypt, encrypt_parallel, par::ParArchive,
+    read_sidecar, sidecar_path, write_sidecar, HashWrapperReader, KeyEntry, KeyRegistry, Mode,
+    PARALLEL_THRESHOLD, READ_BUFFER_SIZE, WRITE_BUFFER_SIZE,
+};
 
 #[derive(Parser)]
 #[command(author, version, about)]
@@ -34,9 +27,20 @@ struct Args {
     #[clap(value_enum, value_parser, default_value = "auto")]
     mode: Mode,
 
-    /// Type of the encrypted PAR file.
-    #[clap(value_enum, value_parser, default_value = "auto")]
-    par_type: ParType,
+    /// Name of the key registry entry to use. Defaults to auto-detecting
+    /// it from the input file's magic bytes (or, failing that, its
+    /// `.sha256` sidecar).
+    #[clap(long, value_parser)]
+    key_name: Option<String>,
+
+    /// Load an additional key file (4-byte magic followed by a 512-byte
+    /// key) into the registry, under the name of the file's stem.
+    #[clap(long, value_parser)]
+    key: Option<PathBuf>,
+
+    /// Load every key file in this directory into the registry.
+    #[clap(long, value_parser)]
+    keys: Option<PathBuf>,
 
     /// Overwrite files without asking.
     #[clap(short, long, action)]
@@ -45,67 +49,69 @@ struct Args {
     /// Skip asking to press ENTER when done.
     #[clap(short, long, action)]
     quick_exit: bool,
-}
-
-#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
-enum Mode {
-    /// Automatically select mode based on input file name.
-    Auto,
-
-    /// Decrypt file.
-    Decrypt,
-
-    /// Encrypt file.
-    Encrypt,
-}
 
-#[derive(Copy, Clone, PartialEq, Eq, ValueEnum)]
-enum ParType {
-    /// Automatically select PAR type based on its contents.
-    Auto,
-
-    /// chara.par.
-    Chara,
-
-    /// chara2.par (Lost Judgment only).
-    Chara2,
+    /// Number of threads to use for the parallel cipher path. Defaults to
+    /// available parallelism. Pass 1 to force the single-threaded path.
+    #[clap(short, long, value_parser)]
+    threads: Option<usize>,
+
+    /// When decrypting, verify the produced plaintext against the
+    /// `.sha256` sidecar written alongside the original encrypted file.
+    #[clap(long, action)]
+    verify: bool,
+
+    /// After decrypting, unpack the resulting PAR archive into this
+    /// directory, preserving its internal directory structure.
+    #[clap(long, value_parser)]
+    extract: Option<PathBuf>,
+
+    /// Before encrypting, repack this directory tree into the PAR archive
+    /// that will be used as the plaintext input.
+    #[clap(long, value_parser)]
+    repack: Option<PathBuf>,
 }
 
-fn encrypt<R, W, K>(reader: R, mut writer: W, mut key: K)
-where
-    R: Read,
-    W: Write,
-    K: Iterator<Item = &'static u64>,
-{
-    for val in reader
-        .bytes()
-        .map(|byte| byte.unwrap())
-        .array_chunks_pad::<8>(0)
-        .enumerate()
-        .map(|(i, bytes)| {
-            u64::from_le_bytes(bytes).rotate_right((i % 64) as u32) ^ key.next().unwrap()
-        })
-    {
-        writer.write(&val.to_le_bytes()).unwrap();
+/// Asks to confirm overwriting `path` if it already exists and `--overwrite`
+/// wasn't passed. Returns `true` if it's fine to proceed.
+fn confirm_overwrite(path: &std::path::Path, overwrite: bool, prompt: &str) -> bool {
+    if overwrite || !path.exists() {
+        return true;
     }
+
+    Confirm::new().with_prompt(prompt).interact().unwrap_or(false)
 }
 
-fn decrypt<R, W, K>(reader: R, mut writer: W, mut key: K)
-where
-    R: Read,
-    W: Write,
-    K: Iterator<Item = &'static u64>,
-{
-    for val in reader
-        .bytes()
-        .map(|byte| byte.unwrap())
-        .array_chunks_pad::<8>(0)
-        .enumerate()
-        .map(|(i, bytes)| {
-            (u64::from_le_bytes(bytes) ^ key.next().unwrap()).rotate_left((i % 64) as u32)
+/// Runs the block cipher over `reader`/`writer`: single-threaded when
+/// `threads` is at most 1 or the input is shorter than `PARALLEL_THRESHOLD`,
+/// otherwise on a Rayon pool of `threads` workers.
+fn run_cipher<R: Read, W: Write>(
+    mode: Mode,
+    reader: R,
+    writer: W,
+    input_len: u64,
+    threads: usize,
+    key_entry: &KeyEntry,
+) {
+    if threads <= 1 || input_len < PARALLEL_THRESHOLD {
+        let key = &key_entry.key;
+
+        match mode {
+            Mode::Encrypt => encrypt(reader, writer, key),
+            Mode::Decrypt => decrypt(reader, writer, key),
+            _ => unreachable!(),
+        }
+    } else {
+        let key = &key_entry.key;
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .unwrap();
+
+        pool.install(|| match mode {
+            Mode::Encrypt => encrypt_parallel(reader, writer, key),
+            Mode::Decrypt => decrypt_parallel(reader, writer, key),
+            _ => unreachable!(),
         })
-    {
-        writer.write(&val.to_le_bytes()).unwrap();
     }
 }
 
@@ -174,42 +180,104 @@ fn main() {
         }
     };
 
-    if !args.overwrite
-        && output.is_file()
-        && !Confirm::new()
-            .with_prompt("File already exists. Overwrite?")
-            .interact()
-            .unwrap_or(false)
-    {
+    if !confirm_overwrite(&output, args.overwrite, "File already exists. Overwrite?") {
         println!("Aborting.");
         return;
     }
 
+    if let Some(repack_dir) = &args.repack {
+        assert!(mode == Mode::Encrypt, "--repack requires encrypt mode");
+        assert!(
+            args.key_name.is_some(),
+            "--repack requires --key-name: the rebuilt plaintext has a PAR \
+             magic, not a cipher magic, so there's nothing to auto-detect a \
+             key from"
+        );
+
+        if !confirm_overwrite(
+            &input,
+            args.overwrite,
+            &format!("{input:?} already exists. Overwrite with the repacked archive?"),
+        ) {
+            println!("Aborting.");
+            return;
+        }
+
+        let archive = ParArchive::from_directory(repack_dir).unwrap();
+        fs::write(&input, archive.to_bytes()).unwrap();
+    }
+
+    let mut registry = KeyRegistry::with_builtins();
+
+    if let Some(key_file) = &args.key {
+        registry.load_key_file(key_file).unwrap();
+    }
+
+    if let Some(keys_dir) = &args.keys {
+        registry.load_keys_dir(keys_dir).unwrap();
+    }
+
     let mut input_file = File::open(&input).unwrap();
     let mut magic_buf = [0; 4];
     input_file.read_exact(&mut magic_buf).unwrap();
     input_file.seek(SeekFrom::Start(0)).unwrap();
 
-    let key = match args.par_type {
-        ParType::Chara => CHARA_KEY,
-        ParType::Chara2 => CHARA2_KEY,
-        ParType::Auto => match &magic_buf {
-            b"\xAC\xC5\x8B\x99" => CHARA_KEY,
-            b"\x01\x6E\x58\xE4" => CHARA2_KEY,
-            _ => {
-                match Select::new()
-                    .with_prompt("Unable to determine PAR type.\nSelect a type:")
-                    .items(&["chara.par", "chara2.par"])
+    let key_entry = if let Some(name) = &args.key_name {
+        registry
+            .by_name(name)
+            .unwrap_or_else(|| panic!("no key named {name:?} in the registry"))
+    } else {
+        let candidates = registry.detect(&magic_buf);
+
+        match candidates.as_slice() {
+            [entry] => {
+                println!("detected key: {}", entry.name);
+                *entry
+            }
+            [] => {
+                let by_digest = if mode == Mode::Decrypt {
+                    read_sidecar(&sidecar_path(&input))
+                        .ok()
+                        .and_then(|expected| {
+                            let detected = detect_by_digest(&mut input_file, &registry, expected);
+                            input_file.seek(SeekFrom::Start(0)).unwrap();
+                            detected
+                        })
+                } else {
+                    None
+                };
+
+                by_digest.unwrap_or_else(|| {
+                    let names: Vec<&str> = registry
+                        .entries
+                        .iter()
+                        .map(|entry| entry.name.as_str())
+                        .collect();
+
+                    let selected = Select::new()
+                        .with_prompt("Unable to determine PAR key.\nSelect one:")
+                        .items(&names)
+                        .clear(false)
+                        .interact()
+                        .expect("key needs to be selected");
+
+                    &registry.entries[selected]
+                })
+            }
+            candidates => {
+                let names: Vec<&str> = candidates.iter().map(|entry| entry.name.as_str()).collect();
+                println!("Ambiguous PAR magic; candidates: {}", names.join(", "));
+
+                let selected = Select::new()
+                    .with_prompt("Select the matching key:")
+                    .items(&names)
                     .clear(false)
                     .interact()
-                    .expect("PAR type needs to be selected")
-                {
-                    0 => CHARA_KEY,
-                    1 => CHARA2_KEY,
-                    _ => unreachable!(),
-                }
+                    .expect("key needs to be selected");
+
+                candidates[selected]
             }
-        },
+        }
     };
 
     let mode_text = match mode {
@@ -227,16 +295,74 @@ writing output to {output:?}
 
     let mut spinner = Spinner::new(Spinners::Line, format!("{mode_text}..."));
 
+    let input_len = input_file.metadata().unwrap().len();
     let reader = BufReader::with_capacity(READ_BUFFER_SIZE, input_file);
-    let writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, File::create(output).unwrap());
-    let key = cast_slice::<_, u64>(key).iter().cycle();
+    let writer = BufWriter::with_capacity(WRITE_BUFFER_SIZE, File::create(&output).unwrap());
+
+    let threads = args
+        .threads
+        .unwrap_or_else(|| std::thread::available_parallelism().map_or(1, |n| n.get()));
 
     match mode {
-        Mode::Encrypt => encrypt(reader, writer, key),
-        Mode::Decrypt => decrypt(reader, writer, key),
+        Mode::Encrypt => {
+            // Digests the plaintext as it streams through the cipher, rather
+            // than reading the input a second time afterwards. The plaintext
+            // is zero-padded to a whole block first, so the digest covers
+            // exactly what decrypting the output produces.
+            let padding = input_len.next_multiple_of(8) - input_len;
+            let mut hasher =
+                HashWrapperReader::new(reader.chain(std::io::repeat(0).take(padding)));
+            run_cipher(mode, &mut hasher, writer, input_len, threads, &key_entry);
+
+            let sidecar = sidecar_path(&output);
+
+            if !confirm_overwrite(
+                &sidecar,
+                args.overwrite,
+                &format!("{sidecar:?} already exists. Overwrite?"),
+            ) {
+                println!("Aborting.");
+                return;
+            }
+
+            write_sidecar(&sidecar, hasher.finalize()).unwrap();
+        }
+        Mode::Decrypt => {
+            run_cipher(mode, reader, writer, input_len, threads, &key_entry);
+
+            if args.verify {
+                let sidecar = sidecar_path(&input);
+                let expected = read_sidecar(&sidecar).unwrap_or_else(|_| {
+                    panic!("--verify requested but no sidecar found at {sidecar:?}")
+                });
+
+                let mut hasher = HashWrapperReader::new(File::open(&output).unwrap());
+                copy(&mut hasher, &mut sink()).unwrap();
+
+                if hasher.finalize() != expected {
+                    panic!("plaintext verification failed: digest mismatch against {sidecar:?}");
+                }
+            }
+        }
         _ => unreachable!(),
     }
 
+    if let Some(extract_dir) = &args.extract {
+        assert!(mode == Mode::Decrypt, "--extract requires decrypt mode");
+
+        if !confirm_overwrite(
+            extract_dir,
+            args.overwrite,
+            &format!("{extract_dir:?} already exists. Overwrite its contents?"),
+        ) {
+            println!("Aborting.");
+            return;
+        }
+
+        let archive = ParArchive::from_bytes(&fs::read(&output).unwrap()).unwrap();
+        archive.extract_to(extract_dir).unwrap();
+    }
+
     spinner.stop_with_newline();
 
     println!();
@@ -0,0 +1,509 @@
+//! The PAR container found inside a decrypted `.par` stream: a fixed
+//! header, a flat table of file/directory entries (each pointing at its
+//! parent by index), and two trailing blobs holding the entry names and
+//! file data that the table's entries offset into.
+
+use std::{
+    fs,
+    io::{self, Read},
+    path::Path,
+};
+
+const MAGIC: &[u8; 4] = b"PARC";
+const VERSION: u32 = 1;
+
+/// On-disk size of a single entry-table record.
+const ENTRY_RECORD_SIZE: usize = 24;
+
+const KIND_FILE: u8 = 0;
+const KIND_DIRECTORY: u8 = 1;
+
+/// Sentinel `parent` value for an entry with no parent, i.e. one that
+/// belongs directly to `ParArchive::entries`.
+const NO_PARENT: i32 = -1;
+
+/// A single node in a PAR archive: either a file with its raw bytes, or a
+/// directory containing more nodes.
+#[derive(Debug, Clone)]
+pub enum ParEntry {
+    File {
+        name: String,
+        data: Vec<u8>,
+    },
+    Directory {
+        name: String,
+        entries: Vec<ParEntry>,
+    },
+}
+
+/// A parsed, or to-be-built, PAR archive: a tree of named entries.
+#[derive(Debug, Clone, Default)]
+pub struct ParArchive {
+    pub entries: Vec<ParEntry>,
+}
+
+/// One entry-table record, as read from or about to be written to the
+/// on-disk entry table. Names and file data live in the trailing blobs;
+/// this only records where to find them.
+struct Record {
+    kind: u8,
+    parent: i32,
+    name_offset: u32,
+    name_len: u32,
+    data_offset: u32,
+    data_len: u32,
+}
+
+impl ParArchive {
+    /// Parses a decrypted PAR stream: header, entry table, name blob, data
+    /// blob.
+    pub fn from_bytes(bytes: &[u8]) -> io::Result<Self> {
+        let mut cursor = bytes;
+
+        let magic: [u8; 4] = read_checked(&mut cursor, 4)?.try_into().unwrap();
+
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a PAR archive",
+            ));
+        }
+
+        let version = read_u32(&mut cursor)?;
+
+        if version != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("unsupported PAR version {version}"),
+            ));
+        }
+
+        let entry_count = read_u32(&mut cursor)? as usize;
+        let _name_blob_offset = read_u32(&mut cursor)?;
+        let _data_blob_offset = read_u32(&mut cursor)?;
+
+        // Bound the claimed entry count against what could actually fit in
+        // the remaining stream before trying to allocate for it; the count
+        // comes straight from untrusted, possibly-corrupt input.
+        if entry_count > cursor.len() / ENTRY_RECORD_SIZE {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "PAR entry count exceeds the size of the stream",
+            ));
+        }
+
+        let mut records = Vec::with_capacity(entry_count);
+
+        for _ in 0..entry_count {
+            records.push(read_record(&mut cursor)?);
+        }
+
+        Ok(Self {
+            entries: build_children(&records, NO_PARENT, bytes)?,
+        })
+    }
+
+    /// Serializes the archive back into a decrypted PAR stream: header,
+    /// entry table, name blob, data blob.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut records = Vec::new();
+        let mut name_blob = Vec::new();
+        let mut data_blob = Vec::new();
+
+        flatten_entries(&self.entries, NO_PARENT, &mut records, &mut name_blob, &mut data_blob);
+
+        let header_len = 4 + 4 + 4 + 4 + 4;
+        let entry_table_len = records.len() * ENTRY_RECORD_SIZE;
+        let name_blob_offset = (header_len + entry_table_len) as u32;
+        let data_blob_offset = name_blob_offset + name_blob.len() as u32;
+
+        let mut out = Vec::with_capacity(
+            header_len + entry_table_len + name_blob.len() + data_blob.len(),
+        );
+
+        out.extend_from_slice(MAGIC);
+        out.extend_from_slice(&VERSION.to_le_bytes());
+        out.extend_from_slice(&(records.len() as u32).to_le_bytes());
+        out.extend_from_slice(&name_blob_offset.to_le_bytes());
+        out.extend_from_slice(&data_blob_offset.to_le_bytes());
+
+        for record in &records {
+            write_record(&mut out, record, name_blob_offset, data_blob_offset);
+        }
+
+        out.extend_from_slice(&name_blob);
+        out.extend_from_slice(&data_blob);
+
+        out
+    }
+
+    /// Recursively writes every file entry under `dir`, preserving the
+    /// archive's directory structure.
+    pub fn extract_to(&self, dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(dir)?;
+        write_entries_to_disk(&self.entries, dir)
+    }
+
+    /// Walks a directory tree and builds the corresponding archive — the
+    /// inverse of `extract_to`.
+    pub fn from_directory(dir: &Path) -> io::Result<Self> {
+        Ok(Self {
+            entries: read_entries_from_disk(dir)?,
+        })
+    }
+}
+
+/// Joins `name` onto `dir`, rejecting anything that could escape it: an
+/// absolute name (which would replace `dir` outright under `Path::join`
+/// semantics) or one containing a `..` component (zip-slip). Entry names
+/// come from the decrypted, untrusted archive, so this must be checked
+/// before every write.
+fn join_entry_path(dir: &Path, name: &str) -> io::Result<std::path::PathBuf> {
+    let name_path = Path::new(name);
+
+    let safe = name_path.is_relative()
+        && !name_path
+            .components()
+            .any(|component| matches!(component, std::path::Component::ParentDir));
+
+    if !safe {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("PAR entry name escapes the extraction directory: {name:?}"),
+        ));
+    }
+
+    Ok(dir.join(name_path))
+}
+
+fn write_entries_to_disk(entries: &[ParEntry], dir: &Path) -> io::Result<()> {
+    for entry in entries {
+        match entry {
+            ParEntry::File { name, data } => fs::write(join_entry_path(dir, name)?, data)?,
+            ParEntry::Directory { name, entries } => {
+                let sub_dir = join_entry_path(dir, name)?;
+                fs::create_dir_all(&sub_dir)?;
+                write_entries_to_disk(entries, &sub_dir)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn read_entries_from_disk(dir: &Path) -> io::Result<Vec<ParEntry>> {
+    let mut dir_entries: Vec<_> = fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    dir_entries.sort_by_key(|entry| entry.file_name());
+
+    let mut entries = Vec::with_capacity(dir_entries.len());
+
+    for entry in dir_entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+
+        entries.push(if entry.file_type()?.is_dir() {
+            ParEntry::Directory {
+                name,
+                entries: read_entries_from_disk(&entry.path())?,
+            }
+        } else {
+            ParEntry::File {
+                name,
+                data: fs::read(entry.path())?,
+            }
+        });
+    }
+
+    Ok(entries)
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> io::Result<u32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+fn read_i32<R: Read>(reader: &mut R) -> io::Result<i32> {
+    let mut buf = [0u8; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(i32::from_le_bytes(buf))
+}
+
+/// Slices `len` bytes off the front of `cursor`, failing instead of
+/// allocating or panicking if `len` (which may come straight from
+/// untrusted input) is larger than what's actually left.
+fn read_checked<'a>(cursor: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if len > cursor.len() {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "PAR stream truncated",
+        ));
+    }
+
+    let (taken, rest) = cursor.split_at(len);
+    *cursor = rest;
+    Ok(taken)
+}
+
+fn read_record(cursor: &mut &[u8]) -> io::Result<Record> {
+    let kind = read_checked(cursor, 1)?[0];
+    read_checked(cursor, 3)?; // padding
+
+    let parent = read_i32(cursor)?;
+    let name_offset = read_u32(cursor)?;
+    let name_len = read_u32(cursor)?;
+    let data_offset = read_u32(cursor)?;
+    let data_len = read_u32(cursor)?;
+
+    if kind != KIND_FILE && kind != KIND_DIRECTORY {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unknown PAR entry kind {kind}"),
+        ));
+    }
+
+    Ok(Record {
+        kind,
+        parent,
+        name_offset,
+        name_len,
+        data_offset,
+        data_len,
+    })
+}
+
+fn write_record(out: &mut Vec<u8>, record: &Record, name_blob_offset: u32, data_blob_offset: u32) {
+    out.push(record.kind);
+    out.extend_from_slice(&[0u8; 3]);
+    out.extend_from_slice(&record.parent.to_le_bytes());
+    out.extend_from_slice(&(name_blob_offset + record.name_offset).to_le_bytes());
+    out.extend_from_slice(&record.name_len.to_le_bytes());
+    out.extend_from_slice(&(data_blob_offset + record.data_offset).to_le_bytes());
+    out.extend_from_slice(&record.data_len.to_le_bytes());
+}
+
+fn record_name(record: &Record, bytes: &[u8]) -> io::Result<String> {
+    let start = record.name_offset as usize;
+    let end = start
+        .checked_add(record.name_len as usize)
+        .filter(|&end| end <= bytes.len())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "PAR name offset out of range"))?;
+
+    String::from_utf8(bytes[start..end].to_vec())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+fn record_data(record: &Record, bytes: &[u8]) -> io::Result<Vec<u8>> {
+    let start = record.data_offset as usize;
+    let end = start
+        .checked_add(record.data_len as usize)
+        .filter(|&end| end <= bytes.len())
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "PAR data offset out of range"))?;
+
+    Ok(bytes[start..end].to_vec())
+}
+
+/// Builds the `ParEntry` tree for every record whose `parent` field equals
+/// the `parent` argument, recursing into directories by their own table index.
+fn build_children(records: &[Record], parent: i32, bytes: &[u8]) -> io::Result<Vec<ParEntry>> {
+    let mut entries = Vec::new();
+
+    for (index, record) in records.iter().enumerate() {
+        if record.parent != parent {
+            continue;
+        }
+
+        let name = record_name(record, bytes)?;
+
+        entries.push(if record.kind == KIND_DIRECTORY {
+            ParEntry::Directory {
+                name,
+                entries: build_children(records, index as i32, bytes)?,
+            }
+        } else {
+            ParEntry::File {
+                name,
+                data: record_data(record, bytes)?,
+            }
+        });
+    }
+
+    Ok(entries)
+}
+
+/// Flattens the entry tree into the table/blob shape `to_bytes` writes out.
+/// `record.name_offset`/`data_offset` are relative to the start of their
+/// blob; `to_bytes` adds the blobs' absolute offsets when it writes each
+/// record out.
+fn flatten_entries(
+    entries: &[ParEntry],
+    parent: i32,
+    records: &mut Vec<Record>,
+    name_blob: &mut Vec<u8>,
+    data_blob: &mut Vec<u8>,
+) {
+    for entry in entries {
+        match entry {
+            ParEntry::File { name, data } => {
+                let name_offset = name_blob.len() as u32;
+                name_blob.extend_from_slice(name.as_bytes());
+
+                let data_offset = data_blob.len() as u32;
+                data_blob.extend_from_slice(data);
+
+                records.push(Record {
+                    kind: KIND_FILE,
+                    parent,
+                    name_offset,
+                    name_len: name.len() as u32,
+                    data_offset,
+                    data_len: data.len() as u32,
+                });
+            }
+            ParEntry::Directory { name, entries } => {
+                let name_offset = name_blob.len() as u32;
+                name_blob.extend_from_slice(name.as_bytes());
+
+                records.push(Record {
+                    kind: KIND_DIRECTORY,
+                    parent,
+                    name_offset,
+                    name_len: name.len() as u32,
+                    data_offset: 0,
+                    data_len: 0,
+                });
+
+                let self_index = (records.len() - 1) as i32;
+                flatten_entries(entries, self_index, records, name_blob, data_blob);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample_archive() -> ParArchive {
+        ParArchive {
+            entries: vec![
+                ParEntry::File {
+                    name: "readme.txt".to_string(),
+                    data: b"hello".to_vec(),
+                },
+                ParEntry::Directory {
+                    name: "data".to_string(),
+                    entries: vec![
+                        ParEntry::File {
+                            name: "a.bin".to_string(),
+                            data: vec![1, 2, 3],
+                        },
+                        ParEntry::Directory {
+                            name: "nested".to_string(),
+                            entries: vec![ParEntry::File {
+                                name: "b.bin".to_string(),
+                                data: vec![],
+                            }],
+                        },
+                    ],
+                },
+            ],
+        }
+    }
+
+    fn names(entries: &[ParEntry]) -> Vec<&str> {
+        entries
+            .iter()
+            .map(|entry| match entry {
+                ParEntry::File { name, .. } => name.as_str(),
+                ParEntry::Directory { name, .. } => name.as_str(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn round_trips_nested_archive() {
+        let archive = sample_archive();
+        let bytes = archive.to_bytes();
+        let parsed = ParArchive::from_bytes(&bytes).unwrap();
+
+        assert_eq!(names(&parsed.entries), names(&archive.entries));
+
+        let ParEntry::Directory { entries, .. } = &parsed.entries[1] else {
+            panic!("expected a directory");
+        };
+        assert_eq!(names(entries), vec!["a.bin", "nested"]);
+
+        let ParEntry::File { data, .. } = &entries[0] else {
+            panic!("expected a file");
+        };
+        assert_eq!(data, &[1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_bad_magic() {
+        let err = ParArchive::from_bytes(b"NOPE").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_truncated_stream() {
+        let bytes = sample_archive().to_bytes();
+        let err = ParArchive::from_bytes(&bytes[..bytes.len() - 1]).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_entry_count_past_stream_end() {
+        let mut bytes = sample_archive().to_bytes();
+        bytes[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let err = ParArchive::from_bytes(&bytes).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn rejects_parent_traversal_in_entry_name() {
+        let tmp = std::env::temp_dir().join(format!(
+            "par-traversal-test-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let archive = ParArchive {
+            entries: vec![ParEntry::File {
+                name: "../escaped.txt".to_string(),
+                data: b"pwned".to_vec(),
+            }],
+        };
+
+        let err = archive.extract_to(&tmp).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!tmp.parent().unwrap().join("escaped.txt").exists());
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+
+    #[test]
+    fn rejects_absolute_entry_name() {
+        let tmp = std::env::temp_dir().join(format!(
+            "par-absolute-test-{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&tmp);
+        fs::create_dir_all(&tmp).unwrap();
+
+        let archive = ParArchive {
+            entries: vec![ParEntry::File {
+                name: "/tmp/escaped_pwned.txt".to_string(),
+                data: b"pwned".to_vec(),
+            }],
+        };
+
+        assert_eq!(
+            archive.extract_to(&tmp).unwrap_err().kind(),
+            io::ErrorKind::InvalidData
+        );
+
+        fs::remove_dir_all(&tmp).unwrap();
+    }
+}